@@ -0,0 +1,7 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+fn main() {
+    prost_build::compile_protos(&["src/remote_write.proto"], &["src"])
+        .expect("Failed to compile remote_write.proto");
+}
@@ -0,0 +1,176 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Periodic textfile output, mirroring `MetricsPusher` but writing the encoded
+//! metrics to a local file instead of pushing them over the network. This
+//! supports the node_exporter textfile collector pattern for hosts where
+//! outbound pushes are blocked.
+
+use libra_logger::{error, info};
+use prometheus::{Encoder, TextEncoder};
+use std::{
+    env, fs,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+const DEFAULT_DUMP_FREQUENCY_SECS: u64 = 15;
+
+/// MetricsDumper periodically writes the gathered Prometheus metrics to a
+/// local file, atomically, so a sidecar node_exporter textfile collector can
+/// pick them up.
+pub struct MetricsDumper;
+
+/// Handle returned by `MetricsDumper::start` that lets the caller request a
+/// clean shutdown of the background dump loop.
+pub struct MetricsDumperHandle {
+    stop_sender: Sender<()>,
+    join_handle: JoinHandle<()>,
+}
+
+impl MetricsDumperHandle {
+    /// Signals the dump loop to stop and joins the background thread. The
+    /// signal wakes the loop immediately rather than waiting for the current
+    /// sleep to elapse, so this returns promptly regardless of
+    /// `METRICS_DUMP_FREQUENCY_SECS`.
+    pub fn stop(self) {
+        // The loop thread holds its own clone of the sender, so this can never
+        // fail with a disconnected receiver.
+        let _ = self.stop_sender.send(());
+        if let Err(e) = self.join_handle.join() {
+            error!("Failed to join metrics dump thread: {:?}", e);
+        }
+    }
+}
+
+impl MetricsDumper {
+    fn dump_once(dump_path: &PathBuf) {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+            error!("Failed to encode dumped metrics: {}.", e.to_string());
+            return;
+        }
+
+        let tmp_path = dump_path.with_extension("tmp");
+        if let Err(e) = fs::write(&tmp_path, &buffer) {
+            error!(
+                "Failed to write metrics dump to {}: {}.",
+                tmp_path.display(),
+                e
+            );
+            return;
+        }
+        if let Err(e) = fs::rename(&tmp_path, dump_path) {
+            error!(
+                "Failed to rename metrics dump {} to {}: {}.",
+                tmp_path.display(),
+                dump_path.display(),
+                e
+            );
+        }
+    }
+
+    fn run(
+        self,
+        dump_path: PathBuf,
+        dump_frequency_secs: u64,
+        // Kept alive for the lifetime of the loop so a dropped `MetricsDumperHandle`
+        // (as opposed to an explicit `stop()`) does not disconnect the channel and
+        // stop the loop early.
+        _stop_sender: Sender<()>,
+        stop_receiver: Receiver<()>,
+    ) {
+        let frequency = Duration::from_secs(dump_frequency_secs);
+        loop {
+            Self::dump_once(&dump_path);
+            match stop_receiver.recv_timeout(frequency) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    /// start starts a new thread and periodically writes the gathered metrics to
+    /// `METRICS_DUMP_DIR`/`METRICS_DUMP_FILE`, creating the directory if needed.
+    pub fn start(self) -> Option<MetricsDumperHandle> {
+        let dump_dir = match env::var("METRICS_DUMP_DIR") {
+            Ok(s) => s,
+            Err(_) => {
+                info!("METRICS_DUMP_DIR env var is not set. Skipping dumping metrics.");
+                return None;
+            }
+        };
+        let dump_file = match env::var("METRICS_DUMP_FILE") {
+            Ok(s) => s,
+            Err(_) => {
+                info!("METRICS_DUMP_FILE env var is not set. Skipping dumping metrics.");
+                return None;
+            }
+        };
+        let dump_frequency_secs = match env::var("METRICS_DUMP_FREQUENCY_SECS") {
+            Ok(s) => match s.parse::<u64>() {
+                Ok(i) => i,
+                Err(_) => {
+                    error!("Invalid value for METRICS_DUMP_FREQUENCY_SECS: {}", s);
+                    return None;
+                }
+            },
+            Err(_) => DEFAULT_DUMP_FREQUENCY_SECS,
+        };
+
+        let dump_path = PathBuf::from(&dump_dir).join(&dump_file);
+        if let Err(e) = fs::create_dir_all(&dump_dir) {
+            error!("Failed to create metrics dump dir {}: {}.", dump_dir, e);
+            return None;
+        }
+        info!(
+            "Starting metrics dump loop. Writing metrics to {} with a frequency of {} seconds",
+            dump_path.display(),
+            dump_frequency_secs
+        );
+        let (stop_sender, stop_receiver) = mpsc::channel();
+        let join_handle = {
+            let thread_stop_sender = stop_sender.clone();
+            thread::spawn(move || {
+                self.run(
+                    dump_path,
+                    dump_frequency_secs,
+                    thread_stop_sender,
+                    stop_receiver,
+                )
+            })
+        };
+        Some(MetricsDumperHandle {
+            stop_sender,
+            join_handle,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_once_writes_through_a_tmp_file_and_leaves_no_tmp_behind() {
+        let dir = env::temp_dir().join(format!(
+            "push-metrics-dumper-test-{:?}",
+            thread::current().id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let dump_path = dir.join("metrics.prom");
+
+        MetricsDumper::dump_once(&dump_path);
+
+        assert!(dump_path.exists(), "dump target file should exist");
+        assert!(
+            !dump_path.with_extension("tmp").exists(),
+            "tmp file should have been renamed away, not left behind"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
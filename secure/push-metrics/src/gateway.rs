@@ -0,0 +1,150 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Builds the pushgateway target (URL, HTTP method, optional basic-auth
+//! header) from environment variables, per the pushgateway grouping spec:
+//! <https://github.com/prometheus/pushgateway#url>.
+
+use libra_logger::{error, info};
+use std::env;
+
+/// Whether to PUT (replace all metrics in the group) or POST (merge with
+/// matching metrics already in the group) to the pushgateway.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PushMethod {
+    Post,
+    Put,
+}
+
+/// A fully resolved pushgateway target: the `/metrics/job/<job>/<label>/<value>...`
+/// URL, the HTTP method to use, and an optional `Authorization` header value.
+pub struct PushGatewayTarget {
+    pub url: String,
+    pub method: PushMethod,
+    pub auth_header: Option<String>,
+}
+
+/// RFC 3986 unreserved characters beyond alphanumerics, which must not be
+/// percent-encoded in a URL path segment.
+const PATH_SEGMENT_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+fn percent_encode(value: &str) -> String {
+    percent_encoding::utf8_percent_encode(value, PATH_SEGMENT_ENCODE_SET).to_string()
+}
+
+/// Builds the `/metrics/job/<job>/<label>/<value>...` path suffix per the
+/// pushgateway grouping spec. `grouping_labels` should already be sorted for a
+/// deterministic path.
+fn build_grouping_path(job: &str, grouping_labels: &[(String, String)]) -> String {
+    let mut path = format!("/metrics/job/{}", percent_encode(job));
+    for (label, value) in grouping_labels {
+        path.push_str(&format!("/{}/{}", percent_encode(label), percent_encode(value)));
+    }
+    path
+}
+
+/// Builds an HTTP `Authorization: Basic` header value for the given credentials.
+fn build_basic_auth_header(username: &str, password: &str) -> String {
+    format!("Basic {}", base64::encode(format!("{}:{}", username, password)))
+}
+
+/// Reads `PUSH_METRICS_ENDPOINT`, `PUSH_METRICS_JOB`, any
+/// `PUSH_METRICS_GROUPING_<KEY>` labels, `PUSH_METRICS_USERNAME`/
+/// `PUSH_METRICS_PASSWORD`, and `PUSH_METRICS_HTTP_METHOD` to build a
+/// `PushGatewayTarget`. Returns `None` (and logs) if a required variable is
+/// missing.
+pub fn resolve_push_gateway_target() -> Option<PushGatewayTarget> {
+    let endpoint = match env::var("PUSH_METRICS_ENDPOINT") {
+        Ok(s) => s,
+        Err(_) => {
+            info!("PUSH_METRICS_ENDPOINT env var is not set. Skipping sending metrics.");
+            return None;
+        }
+    };
+
+    let mut grouping_labels: Vec<(String, String)> = env::vars()
+        .filter_map(|(key, value)| {
+            key.strip_prefix("PUSH_METRICS_GROUPING_")
+                .map(|label| (label.to_lowercase(), value))
+        })
+        .collect();
+    // Sort for a deterministic path, independent of env var iteration order.
+    grouping_labels.sort();
+
+    let url = match env::var("PUSH_METRICS_JOB") {
+        Ok(job) => format!(
+            "{}{}",
+            endpoint.trim_end_matches('/'),
+            build_grouping_path(&job, &grouping_labels)
+        ),
+        Err(_) => {
+            // PUSH_METRICS_JOB is new; existing deployments that only set
+            // PUSH_METRICS_ENDPOINT expect it to be used verbatim, as the full
+            // "http://host:9091/metrics/job/<job>" URL, as before. Fall back to
+            // that rather than silently dropping every push.
+            if !grouping_labels.is_empty() {
+                error!(
+                    "PUSH_METRICS_GROUPING_* labels are set but PUSH_METRICS_JOB is not; grouping labels require PUSH_METRICS_JOB and will be ignored. Falling back to PUSH_METRICS_ENDPOINT as the full pushgateway URL."
+                );
+            } else {
+                info!(
+                    "PUSH_METRICS_JOB env var is not set. Treating PUSH_METRICS_ENDPOINT as the full pushgateway URL."
+                );
+            }
+            endpoint
+        }
+    };
+
+    let method = match env::var("PUSH_METRICS_HTTP_METHOD") {
+        Ok(s) if s.eq_ignore_ascii_case("PUT") => PushMethod::Put,
+        _ => PushMethod::Post,
+    };
+
+    let auth_header = match (
+        env::var("PUSH_METRICS_USERNAME"),
+        env::var("PUSH_METRICS_PASSWORD"),
+    ) {
+        (Ok(username), Ok(password)) => Some(build_basic_auth_header(&username, &password)),
+        _ => None,
+    };
+
+    Some(PushGatewayTarget {
+        url,
+        method,
+        auth_header,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grouping_path_url_encodes_job_and_labels() {
+        let labels = vec![
+            ("instance".to_string(), "host:1".to_string()),
+            ("zone".to_string(), "us east".to_string()),
+        ];
+        assert_eq!(
+            build_grouping_path("safety rules", &labels),
+            "/metrics/job/safety%20rules/instance/host%3A1/zone/us%20east"
+        );
+    }
+
+    #[test]
+    fn grouping_path_with_no_labels_is_just_the_job() {
+        assert_eq!(build_grouping_path("safety_rules", &[]), "/metrics/job/safety_rules");
+    }
+
+    #[test]
+    fn basic_auth_header_base64_encodes_user_and_password() {
+        assert_eq!(
+            build_basic_auth_header("alice", "secret"),
+            "Basic YWxpY2U6c2VjcmV0"
+        );
+    }
+}
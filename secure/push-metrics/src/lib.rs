@@ -10,47 +10,135 @@ pub use prometheus::{
     IntGauge, IntGaugeVec,
 };
 
+mod dumper;
+mod gateway;
+mod remote_write;
+mod server;
+mod sourced;
+pub use dumper::{MetricsDumper, MetricsDumperHandle};
+pub use gateway::PushMethod;
+pub use server::MetricsServer;
+pub use sourced::{register_sourced_counter, register_sourced_gauge, SourcedMetric, SourcedMetricKind};
+
+use gateway::PushGatewayTarget;
+
 use libra_logger::{error, info};
 use prometheus::{Encoder, TextEncoder};
-use std::{env, thread, thread::JoinHandle, time::Duration};
+use std::{
+    env,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    thread::JoinHandle,
+    time::Duration,
+};
 
 const DEFAULT_PUSH_FREQUENCY_SECS: u64 = 15;
+const REMOTE_WRITE_PROTOCOL: &str = "remote_write";
 
 /// MetricsPusher provides a function to push a list of Metrics to a configurable
 /// pushgateway endpoint.
 pub struct MetricsPusher;
 
+/// Handle returned by `MetricsPusher::start` that lets the caller request a clean
+/// shutdown of the background push loop. Dropping the handle without calling
+/// `stop` leaves the loop running until the process exits.
+pub struct MetricsPusherHandle {
+    stop_sender: Sender<()>,
+    join_handle: JoinHandle<()>,
+}
+
+impl MetricsPusherHandle {
+    /// Signals the push loop to stop, waits for it to perform one final push, and
+    /// joins the background thread. The signal wakes the loop immediately rather
+    /// than waiting for the current sleep to elapse, so this returns promptly
+    /// regardless of `PUSH_METRICS_FREQUENCY_SECS`. This is the mechanism
+    /// batch/ephemeral jobs should use to guarantee their last round of metrics
+    /// reaches the pushgateway before the process exits.
+    pub fn stop(self) {
+        // The loop thread holds its own clone of the sender, so this can never
+        // fail with a disconnected receiver.
+        let _ = self.stop_sender.send(());
+        if let Err(e) = self.join_handle.join() {
+            error!("Failed to join push metrics thread: {:?}", e);
+        }
+    }
+}
+
 impl MetricsPusher {
-    fn run(self, push_metrics_endpoint: String, push_metrics_frequency_secs: u64) {
+    fn push_to(target: &PushGatewayTarget) {
+        // eg value for PUSH_METRICS_PROTOCOL: "remote_write". Anything else (or
+        // unset) keeps the default pushgateway text protocol.
+        let use_remote_write = env::var("PUSH_METRICS_PROTOCOL")
+            .map(|s| s == REMOTE_WRITE_PROTOCOL)
+            .unwrap_or(false);
+        if use_remote_write {
+            remote_write::push_remote_write(&target.url);
+            return;
+        }
+
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+            error!("Failed to encode push metrics: {}.", e.to_string());
+            return;
+        }
+        let mut request = match target.method {
+            PushMethod::Post => ureq::post(&target.url),
+            PushMethod::Put => ureq::put(&target.url),
+        };
+        request.timeout_connect(10_000);
+        if let Some(auth_header) = &target.auth_header {
+            request.set("Authorization", auth_header);
+        }
+        let response = request.send_bytes(&buffer);
+        if let Some(error) = response.synthetic_error() {
+            error!(
+                "Failed to push metrics to {}. Error: {}",
+                target.url, error
+            );
+        }
+    }
+
+    fn run(
+        self,
+        target: PushGatewayTarget,
+        push_metrics_frequency_secs: u64,
+        // Kept alive for the lifetime of the loop so a dropped `MetricsPusherHandle`
+        // (as opposed to an explicit `stop()`) does not disconnect the channel and
+        // stop the loop early.
+        _stop_sender: Sender<()>,
+        stop_receiver: Receiver<()>,
+    ) {
+        let frequency = Duration::from_secs(push_metrics_frequency_secs);
         loop {
-            let mut buffer = Vec::new();
-            if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
-                error!("Failed to encode push metrics: {}.", e.to_string());
-            } else {
-                let response = ureq::post(&push_metrics_endpoint)
-                    .timeout_connect(10_000)
-                    .send_bytes(&buffer);
-                if let Some(error) = response.synthetic_error() {
-                    error!(
-                        "Failed to push metrics to {}. Error: {}",
-                        push_metrics_endpoint, error
-                    );
-                }
+            Self::push_to(&target);
+            match stop_receiver.recv_timeout(frequency) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
             }
-            thread::sleep(Duration::from_secs(push_metrics_frequency_secs));
         }
+        info!("Push metrics loop stopping. Performing final push before exit.");
+        Self::push_to(&target);
     }
 
-    /// start starts a new thread and periodically pushes the metrics to a pushgateway endpoint
-    pub fn start(self) -> Option<JoinHandle<()>> {
-        // eg value for PUSH_METRICS_ENDPOINT: "http://pushgatewar.server.com:9091/metrics/job/safety_rules"
-        let push_metrics_endpoint = match env::var("PUSH_METRICS_ENDPOINT") {
-            Ok(s) => s,
-            Err(_) => {
-                info!("PUSH_METRICS_ENDPOINT env var is not set. Skipping sending metrics.");
-                return None;
-            }
+    /// push_once gathers and pushes the current metrics a single time. Useful for
+    /// short-lived batch jobs that want a guaranteed push right before exit
+    /// instead of paying for a background thread they may not live long enough
+    /// to benefit from.
+    pub fn push_once() {
+        let target = match gateway::resolve_push_gateway_target() {
+            Some(target) => target,
+            None => return,
         };
+        Self::push_to(&target);
+    }
+
+    /// start starts a new thread and periodically pushes the metrics to a pushgateway endpoint.
+    /// Returns a `MetricsPusherHandle` that can be used to stop the loop and force a final push.
+    pub fn start(self) -> Option<MetricsPusherHandle> {
+        // eg value for PUSH_METRICS_ENDPOINT: "http://pushgateway.server.com:9091" when
+        // PUSH_METRICS_JOB is set, otherwise the full URL, eg
+        // "http://pushgateway.server.com:9091/metrics/job/safety_rules"
+        let target = gateway::resolve_push_gateway_target()?;
         let push_metrics_frequency_secs = match env::var("PUSH_METRICS_FREQUENCY_SECS") {
             Ok(s) => match s.parse::<u64>() {
                 Ok(i) => i,
@@ -63,10 +151,23 @@ impl MetricsPusher {
         };
         info!(
             "Starting push metrics loop. Sending metrics to {} with a frequency of {} seconds",
-            push_metrics_endpoint, push_metrics_frequency_secs
+            target.url, push_metrics_frequency_secs
         );
-        Some(thread::spawn(move || {
-            self.run(push_metrics_endpoint, push_metrics_frequency_secs)
-        }))
+        let (stop_sender, stop_receiver) = mpsc::channel();
+        let join_handle = {
+            let thread_stop_sender = stop_sender.clone();
+            thread::spawn(move || {
+                self.run(
+                    target,
+                    push_metrics_frequency_secs,
+                    thread_stop_sender,
+                    stop_receiver,
+                )
+            })
+        };
+        Some(MetricsPusherHandle {
+            stop_sender,
+            join_handle,
+        })
     }
 }
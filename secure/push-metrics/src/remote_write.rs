@@ -0,0 +1,310 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Alternative push backend that speaks the Prometheus remote-write protocol
+//! instead of the pushgateway text format, for sending into aggregating
+//! proxies rather than a pushgateway.
+
+use libra_logger::error;
+use prometheus::proto::{MetricFamily, MetricType};
+use prost::Message;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod proto {
+    include!(concat!(env!("OUT_DIR"), "/remote_write.rs"));
+}
+
+use proto::{Label, Sample, TimeSeries, WriteRequest};
+
+fn label(name: &str, value: &str) -> Label {
+    Label {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
+
+/// Formats a bucket bound / quantile for use as a label value, matching the
+/// Prometheus exposition format convention (`prometheus::TextEncoder`) of
+/// `"+Inf"`/`"-Inf"` rather than Rust's default `f64::to_string()`, which
+/// renders infinities as `"inf"`/`"-inf"`.
+fn format_label_value(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn timeseries(metric_name: &str, base_labels: &[Label], le_or_quantile: Option<Label>, value: f64, timestamp_ms: i64) -> TimeSeries {
+    let mut labels = Vec::with_capacity(base_labels.len() + 2);
+    labels.push(label("__name__", metric_name));
+    labels.extend_from_slice(base_labels);
+    if let Some(extra) = le_or_quantile {
+        labels.push(extra);
+    }
+    // The remote-write spec requires label names within a series to be sorted
+    // lexicographically; spec-compliant receivers reject out-of-order series.
+    labels.sort_by(|a, b| a.name.cmp(&b.name));
+    TimeSeries {
+        labels,
+        samples: vec![Sample {
+            value,
+            timestamp: timestamp_ms,
+        }],
+    }
+}
+
+/// Converts gathered `MetricFamily` protos into a remote-write `WriteRequest`,
+/// expanding histograms and summaries into their `_bucket`/`_sum`/`_count`
+/// (respectively `_sum`/`_count` plus per-quantile) component series.
+fn metric_families_to_write_request(families: &[MetricFamily], timestamp_ms: i64) -> WriteRequest {
+    let mut timeseries_list = Vec::new();
+
+    for family in families {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let base_labels: Vec<Label> = metric
+                .get_label()
+                .iter()
+                .map(|l| label(l.get_name(), l.get_value()))
+                .collect();
+
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    timeseries_list.push(timeseries(
+                        name,
+                        &base_labels,
+                        None,
+                        metric.get_counter().get_value(),
+                        timestamp_ms,
+                    ));
+                }
+                MetricType::GAUGE => {
+                    timeseries_list.push(timeseries(
+                        name,
+                        &base_labels,
+                        None,
+                        metric.get_gauge().get_value(),
+                        timestamp_ms,
+                    ));
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        timeseries_list.push(timeseries(
+                            &format!("{}_bucket", name),
+                            &base_labels,
+                            Some(label("le", &format_label_value(bucket.get_upper_bound()))),
+                            bucket.get_cumulative_count() as f64,
+                            timestamp_ms,
+                        ));
+                    }
+                    timeseries_list.push(timeseries(
+                        &format!("{}_sum", name),
+                        &base_labels,
+                        None,
+                        histogram.get_sample_sum(),
+                        timestamp_ms,
+                    ));
+                    timeseries_list.push(timeseries(
+                        &format!("{}_count", name),
+                        &base_labels,
+                        None,
+                        histogram.get_sample_count() as f64,
+                        timestamp_ms,
+                    ));
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        timeseries_list.push(timeseries(
+                            name,
+                            &base_labels,
+                            Some(label("quantile", &format_label_value(quantile.get_quantile()))),
+                            quantile.get_value(),
+                            timestamp_ms,
+                        ));
+                    }
+                    timeseries_list.push(timeseries(
+                        &format!("{}_sum", name),
+                        &base_labels,
+                        None,
+                        summary.get_sample_sum(),
+                        timestamp_ms,
+                    ));
+                    timeseries_list.push(timeseries(
+                        &format!("{}_count", name),
+                        &base_labels,
+                        None,
+                        summary.get_sample_count() as f64,
+                        timestamp_ms,
+                    ));
+                }
+                MetricType::UNTYPED => {
+                    timeseries_list.push(timeseries(
+                        name,
+                        &base_labels,
+                        None,
+                        metric.get_untyped().get_value(),
+                        timestamp_ms,
+                    ));
+                }
+            }
+        }
+    }
+
+    WriteRequest {
+        timeseries: timeseries_list,
+    }
+}
+
+/// Encodes the currently gathered metrics as a Snappy-compressed remote-write
+/// `WriteRequest` and POSTs it to `remote_write_endpoint` with the headers
+/// required by the protocol.
+pub fn push_remote_write(remote_write_endpoint: &str) {
+    let timestamp_ms = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as i64,
+        Err(e) => {
+            error!("System clock is before the UNIX epoch: {}.", e);
+            return;
+        }
+    };
+
+    let write_request = metric_families_to_write_request(&prometheus::gather(), timestamp_ms);
+    let mut encoded = Vec::with_capacity(write_request.encoded_len());
+    if let Err(e) = write_request.encode(&mut encoded) {
+        error!("Failed to encode remote-write request: {}.", e);
+        return;
+    }
+
+    let compressed = match snap::raw::Encoder::new().compress_vec(&encoded) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Failed to snappy-compress remote-write request: {}.", e);
+            return;
+        }
+    };
+
+    let response = ureq::post(remote_write_endpoint)
+        .set("Content-Encoding", "snappy")
+        .set("Content-Type", "application/x-protobuf")
+        .set("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .timeout_connect(10_000)
+        .send_bytes(&compressed);
+    if let Some(error) = response.synthetic_error() {
+        error!(
+            "Failed to push metrics via remote-write to {}. Error: {}",
+            remote_write_endpoint, error
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::proto::{Bucket, Counter, Histogram, LabelPair, Metric, MetricFamily};
+
+    fn make_label(name: &str, value: &str) -> LabelPair {
+        let mut label = LabelPair::default();
+        label.set_name(name.to_string());
+        label.set_value(value.to_string());
+        label
+    }
+
+    #[test]
+    fn counter_family_becomes_single_series_with_name_label() {
+        let mut counter = Counter::default();
+        counter.set_value(42.0);
+        let mut metric = Metric::default();
+        metric.set_counter(counter);
+        metric.set_label(vec![make_label("zone", "us-east")].into());
+
+        let mut family = MetricFamily::default();
+        family.set_name("requests_total".to_string());
+        family.set_field_type(MetricType::COUNTER);
+        family.set_metric(vec![metric].into());
+
+        let write_request = metric_families_to_write_request(&[family], 1_000);
+        assert_eq!(write_request.timeseries.len(), 1);
+        let series = &write_request.timeseries[0];
+        assert_eq!(series.samples, vec![Sample { value: 42.0, timestamp: 1_000 }]);
+
+        let names: Vec<&str> = series.labels.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["__name__", "zone"]);
+    }
+
+    #[test]
+    fn histogram_family_expands_into_bucket_sum_count_series_with_sorted_labels() {
+        let mut bucket = Bucket::default();
+        bucket.set_upper_bound(1.0);
+        bucket.set_cumulative_count(5);
+        let mut histogram = Histogram::default();
+        histogram.set_bucket(vec![bucket].into());
+        histogram.set_sample_sum(12.5);
+        histogram.set_sample_count(5);
+
+        let mut metric = Metric::default();
+        metric.set_histogram(histogram);
+        // "path" sorts after "le" lexicographically, so a naive append-last
+        // implementation would emit labels out of order for this series.
+        metric.set_label(vec![make_label("path", "/foo")].into());
+
+        let mut family = MetricFamily::default();
+        family.set_name("latency".to_string());
+        family.set_field_type(MetricType::HISTOGRAM);
+        family.set_metric(vec![metric].into());
+
+        let write_request = metric_families_to_write_request(&[family], 2_000);
+        // 1 bucket series + _sum + _count
+        assert_eq!(write_request.timeseries.len(), 3);
+
+        let bucket_series = write_request
+            .timeseries
+            .iter()
+            .find(|ts| ts.labels.iter().any(|l| l.name == "le"))
+            .expect("expected a bucket series carrying the le label");
+        let names: Vec<&str> = bucket_series.labels.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["__name__", "le", "path"]);
+    }
+
+    #[test]
+    fn format_label_value_matches_the_exposition_format_infinity_convention() {
+        assert_eq!(format_label_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_label_value(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(format_label_value(0.5), "0.5");
+    }
+
+    #[test]
+    fn histogram_plus_inf_bucket_uses_exposition_format_label() {
+        let mut bucket = Bucket::default();
+        bucket.set_upper_bound(f64::INFINITY);
+        bucket.set_cumulative_count(5);
+        let mut histogram = Histogram::default();
+        histogram.set_bucket(vec![bucket].into());
+        histogram.set_sample_sum(12.5);
+        histogram.set_sample_count(5);
+
+        let mut metric = Metric::default();
+        metric.set_histogram(histogram);
+
+        let mut family = MetricFamily::default();
+        family.set_name("latency".to_string());
+        family.set_field_type(MetricType::HISTOGRAM);
+        family.set_metric(vec![metric].into());
+
+        let write_request = metric_families_to_write_request(&[family], 3_000);
+        let bucket_series = write_request
+            .timeseries
+            .iter()
+            .find(|ts| ts.labels.iter().any(|l| l.name == "le"))
+            .expect("expected a bucket series carrying the le label");
+        let le_value = bucket_series
+            .labels
+            .iter()
+            .find(|l| l.name == "le")
+            .map(|l| l.value.as_str());
+        assert_eq!(le_value, Some("+Inf"));
+    }
+}
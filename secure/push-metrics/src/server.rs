@@ -0,0 +1,83 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pull-mode metrics endpoint: binds a small embedded HTTP server and serves
+//! `GET /metrics` for Prometheus to scrape directly, as an alternative to
+//! `MetricsPusher`'s push model.
+
+use libra_logger::{error, info};
+use prometheus::{Encoder, TextEncoder};
+use std::{env, thread, thread::JoinHandle};
+use tiny_http::{Method, Response, Server};
+
+const DEFAULT_METRICS_SERVER_ADDRESS: &str = "0.0.0.0:9101";
+
+/// MetricsServer binds an HTTP endpoint and serves the process' gathered
+/// Prometheus metrics on every `GET /metrics` request.
+pub struct MetricsServer;
+
+fn is_metrics_scrape(method: &Method, url: &str) -> bool {
+    method == &Method::Get && url == "/metrics"
+}
+
+impl MetricsServer {
+    fn run(self, server: Server) {
+        for request in server.incoming_requests() {
+            if !is_metrics_scrape(request.method(), request.url()) {
+                let response = Response::from_string("Not Found").with_status_code(404);
+                if let Err(e) = request.respond(response) {
+                    error!("Failed to respond to metrics scrape request: {}.", e);
+                }
+                continue;
+            }
+
+            let mut buffer = Vec::new();
+            let response = match TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+                Ok(()) => Response::from_data(buffer).with_header(
+                    "Content-Type: text/plain; version=0.0.4"
+                        .parse::<tiny_http::Header>()
+                        .expect("Content-Type header is valid"),
+                ),
+                Err(e) => {
+                    error!("Failed to encode metrics for scrape: {}.", e.to_string());
+                    Response::from_string("Failed to encode metrics").with_status_code(500)
+                }
+            };
+            if let Err(e) = request.respond(response) {
+                error!("Failed to respond to metrics scrape request: {}.", e);
+            }
+        }
+    }
+
+    /// start binds `METRICS_SERVER_ADDRESS` (default `0.0.0.0:9101`) and serves
+    /// `GET /metrics` on its own thread.
+    pub fn start(self) -> Option<JoinHandle<()>> {
+        let metrics_server_address = env::var("METRICS_SERVER_ADDRESS")
+            .unwrap_or_else(|_| DEFAULT_METRICS_SERVER_ADDRESS.to_string());
+        let server = match Server::http(&metrics_server_address) {
+            Ok(server) => server,
+            Err(e) => {
+                error!(
+                    "Failed to bind metrics server to {}: {}.",
+                    metrics_server_address, e
+                );
+                return None;
+            }
+        };
+        info!("Serving metrics on http://{}/metrics", metrics_server_address);
+        Some(thread::spawn(move || self.run(server)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_get_metrics_is_treated_as_a_scrape() {
+        assert!(is_metrics_scrape(&Method::Get, "/metrics"));
+        assert!(!is_metrics_scrape(&Method::Post, "/metrics"));
+        assert!(!is_metrics_scrape(&Method::Get, "/"));
+        assert!(!is_metrics_scrape(&Method::Get, "/metrics/"));
+    }
+}
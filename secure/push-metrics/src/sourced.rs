@@ -0,0 +1,222 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Support for metrics whose value already lives somewhere else in the process
+//! (e.g. a network bandwidth counter tracked in an `AtomicU64`) and should be
+//! read live at `gather()` time instead of being duplicated into a dedicated
+//! Prometheus atomic.
+
+use prometheus::{
+    core::{Collector, Desc},
+    proto::{LabelPair, Metric, MetricFamily, MetricType},
+    Opts, Result,
+};
+
+/// Whether a `SourcedMetric`'s value is expected to be monotonically
+/// non-decreasing (a counter) or may move in either direction (a gauge).
+///
+/// Sourced counters are not enforced to be monotonic at runtime -- the caller
+/// is trusted to only register a source function that upholds the contract,
+/// same as a real `IntCounter` would be.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourcedMetricKind {
+    Counter,
+    Gauge,
+}
+
+/// A metric backed by a closure that is invoked at `gather()` time rather than
+/// by a value recorded into the crate's own storage. One `SourcedMetric`
+/// represents a single time series, i.e. one fixed combination of label
+/// values.
+pub struct SourcedMetric {
+    desc: Desc,
+    kind: SourcedMetricKind,
+    label_values: Vec<String>,
+    source_fn: Box<dyn Fn() -> f64 + Send + Sync>,
+}
+
+impl SourcedMetric {
+    fn new(
+        name: &str,
+        help: &str,
+        kind: SourcedMetricKind,
+        label_names: &[&str],
+        label_values: &[&str],
+        source_fn: Box<dyn Fn() -> f64 + Send + Sync>,
+    ) -> Result<Self> {
+        debug_assert_eq!(
+            label_names.len(),
+            label_values.len(),
+            "number of variable label names must match the number of label values supplied"
+        );
+        let opts = Opts::new(name, help).variable_labels(label_names.iter().map(|s| s.to_string()).collect());
+        let desc = Desc::new(
+            opts.fq_name(),
+            opts.help.clone(),
+            opts.variable_labels.clone(),
+            opts.const_labels.clone(),
+        )?;
+        Ok(SourcedMetric {
+            desc,
+            kind,
+            label_values: label_values.iter().map(|s| s.to_string()).collect(),
+            source_fn,
+        })
+    }
+}
+
+impl Collector for SourcedMetric {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let value = (self.source_fn)();
+
+        let mut label_pairs = Vec::with_capacity(self.label_values.len());
+        for (name, value) in self.desc.variable_labels.iter().zip(self.label_values.iter()) {
+            let mut label_pair = LabelPair::default();
+            label_pair.set_name(name.clone());
+            label_pair.set_value(value.clone());
+            label_pairs.push(label_pair);
+        }
+
+        let mut metric = Metric::default();
+        metric.set_label(label_pairs.into());
+        match self.kind {
+            SourcedMetricKind::Counter => {
+                let mut counter = prometheus::proto::Counter::default();
+                counter.set_value(value);
+                metric.set_counter(counter);
+            }
+            SourcedMetricKind::Gauge => {
+                let mut gauge = prometheus::proto::Gauge::default();
+                gauge.set_value(value);
+                metric.set_gauge(gauge);
+            }
+        }
+
+        let mut family = MetricFamily::default();
+        family.set_name(self.desc.fq_name.clone());
+        family.set_help(self.desc.help.clone());
+        family.set_field_type(match self.kind {
+            SourcedMetricKind::Counter => MetricType::COUNTER,
+            SourcedMetricKind::Gauge => MetricType::GAUGE,
+        });
+        family.set_metric(vec![metric].into());
+        vec![family]
+    }
+}
+
+/// Registers a sourced counter: a time series whose value is fetched from
+/// `source_fn` at every `gather()` rather than recorded into an `IntCounter`.
+/// `source_fn` must return a value that never decreases, per the Prometheus
+/// counter contract.
+pub fn register_sourced_counter<F>(
+    name: &str,
+    help: &str,
+    label_names: &[&str],
+    label_values: &[&str],
+    source_fn: F,
+) -> Result<()>
+where
+    F: Fn() -> f64 + Send + Sync + 'static,
+{
+    let metric = SourcedMetric::new(
+        name,
+        help,
+        SourcedMetricKind::Counter,
+        label_names,
+        label_values,
+        Box::new(source_fn),
+    )?;
+    prometheus::register(Box::new(metric))
+}
+
+/// Registers a sourced gauge: a time series whose value is fetched from
+/// `source_fn` at every `gather()` rather than recorded into an `IntGauge`.
+pub fn register_sourced_gauge<F>(
+    name: &str,
+    help: &str,
+    label_names: &[&str],
+    label_values: &[&str],
+    source_fn: F,
+) -> Result<()>
+where
+    F: Fn() -> f64 + Send + Sync + 'static,
+{
+    let metric = SourcedMetric::new(
+        name,
+        help,
+        SourcedMetricKind::Gauge,
+        label_names,
+        label_values,
+        Box::new(source_fn),
+    )?;
+    prometheus::register(Box::new(metric))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn collect_reads_the_live_value_from_the_source_fn() {
+        let source = Arc::new(AtomicU64::new(7));
+        let collector_source = source.clone();
+        let metric = SourcedMetric::new(
+            "bandwidth_bytes",
+            "help text",
+            SourcedMetricKind::Counter,
+            &["direction"],
+            &["rx"],
+            Box::new(move || collector_source.load(Ordering::SeqCst) as f64),
+        )
+        .unwrap();
+
+        let families = metric.collect();
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].get_metric()[0].get_counter().get_value(), 7.0);
+
+        // The next gather() should observe the updated value, not a stale one.
+        source.store(42, Ordering::SeqCst);
+        let families = metric.collect();
+        assert_eq!(families[0].get_metric()[0].get_counter().get_value(), 42.0);
+    }
+
+    #[test]
+    fn collect_emits_the_fixed_label_values() {
+        let metric = SourcedMetric::new(
+            "bandwidth_bytes",
+            "help text",
+            SourcedMetricKind::Gauge,
+            &["direction", "peer"],
+            &["tx", "validator-1"],
+            Box::new(|| 0.0),
+        )
+        .unwrap();
+
+        let families = metric.collect();
+        let label_pairs = families[0].get_metric()[0].get_label();
+        let labels: Vec<(&str, &str)> = label_pairs
+            .iter()
+            .map(|l| (l.get_name(), l.get_value()))
+            .collect();
+        assert_eq!(labels, vec![("direction", "tx"), ("peer", "validator-1")]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_debug_asserts_label_names_and_values_are_the_same_length() {
+        let _ = SourcedMetric::new(
+            "bandwidth_bytes",
+            "help text",
+            SourcedMetricKind::Gauge,
+            &["direction", "peer"],
+            &["tx"],
+            Box::new(|| 0.0),
+        );
+    }
+}